@@ -1,7 +1,18 @@
-use std::{env, fs, io::Write, path::PathBuf, process::Command};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex, OnceLock},
+};
 
 use anyhow::Context;
 use clap::Parser;
+use rayon::prelude::*;
+
+mod svg_metadata;
+mod term_preview;
 
 #[derive(Debug, clap::Parser)]
 #[command(disable_help_flag = true)]
@@ -10,11 +21,13 @@ struct Args {
     #[clap(long, action = clap::ArgAction::Help)]
     help: Option<bool>,
 
-    /// Set the X resolution of the image in pixels per inch.
+    /// Set the X resolution of the image in pixels per inch. Ignored if `--width` or `--height` is
+    /// given, since explicit pixel dimensions always take precedence over DPI.
     #[clap(short = 'd', long = "dpi-x", default_value = "90")]
     dpi_x: f64,
 
-    /// Set the Y resolution of the image in pixels per inch.
+    /// Set the Y resolution of the image in pixels per inch. Ignored if `--width` or `--height` is
+    /// given, since explicit pixel dimensions always take precedence over DPI.
     #[clap(short = 'p', long = "dpi-y", default_value = "90")]
     dpi_y: f64,
 
@@ -27,12 +40,14 @@ struct Args {
     y_zoom: f64,
 
     /// Specify how wide you wish the image to be. If unspecified, the natural width of the image
-    /// is used as the default.
+    /// is used as the default. Takes precedence over `--dpi-x` and `--dpi-y`: if `--width` is
+    /// given, both DPI values are ignored for sizing.
     #[clap(short, long)]
     width: Option<u64>,
 
     /// Specify how tall you wish the image to be. If unspecified, the natural width of the image
-    /// is used as the default.
+    /// is used as the default. Takes precedence over `--dpi-x` and `--dpi-y`: if `--height` is
+    /// given, both DPI values are ignored for sizing.
     #[clap(short, long)]
     height: Option<u64>,
 
@@ -46,17 +61,39 @@ struct Args {
     #[clap(short = 'a', long = "keep-aspect-ratio")]
     keep_aspect_ratio: bool,
 
-    /// Input file, stdin if not present.
-    input: Option<PathBuf>,
+    /// Quality (1-100) used when encoding to `jpg`/`jpeg`. Ignored for other formats: `webp` is
+    /// always encoded losslessly, since the `image` crate has no lossy WebP encoder.
+    #[clap(long, default_value = "80")]
+    quality: u8,
+
+    /// Background color used to flatten transparency when encoding to a format that has no
+    /// alpha channel (e.g. `jpg`), given as a `#rrggbb` hex string.
+    #[clap(long, default_value = "#ffffff")]
+    background: String,
+
+    /// Resampling filter used to scale the two axes independently when `--dpi-x` and `--dpi-y`
+    /// differ. One of: nearest, triangle, catmull-rom, gaussian, lanczos3.
+    #[clap(long, default_value = "lanczos3")]
+    resize_filter: String,
 
-    /// Output file, stdout if not present.
+    /// Input file(s), stdin if none are given. Multiple inputs require `--output-dir` and are
+    /// converted in parallel.
+    input: Vec<PathBuf>,
+
+    /// Output file, stdout if not present. Can't be combined with multiple inputs.
     #[clap(long, short)]
     output: Option<PathBuf>,
+
+    /// Directory to write outputs to when converting multiple inputs. Each output filename is
+    /// the input's file stem plus the extension for `--format`.
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
 }
 
-fn hash_input(opt: &Args) -> Option<String> {
+fn hash_input(opt: &Args, input: Option<&Path>) -> Option<String> {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
+    use std::io::Read;
 
     let mut hasher = DefaultHasher::new();
     ((opt.dpi_x * 1000.0) as u64).hash(&mut hasher);
@@ -67,90 +104,302 @@ fn hash_input(opt: &Args) -> Option<String> {
     opt.height.hash(&mut hasher);
     opt.format.hash(&mut hasher);
     opt.keep_aspect_ratio.hash(&mut hasher);
+    opt.quality.hash(&mut hasher);
+    opt.background.hash(&mut hasher);
+    opt.resize_filter.hash(&mut hasher);
+
+    // Note: If the input is from `stdin` then never use a cached image, since there's nothing
+    // to hash that's cheaper than the conversion itself.
+    let path = input?;
 
-    // Note: If the input is from `stdin` then never use a cached image.
-    // TODO: consider hashing the contents of the file instead of the filename, however this is not
-    // a huge issue since pandoc already does this.
-    let path = opt.input.as_ref()?;
-    path.hash(&mut hasher);
+    // Hash the file's contents rather than its path, so editing a file in place (or two
+    // different files sharing a path across build trees) doesn't collide on a stale entry.
+    // Stream it through the hasher in chunks to avoid loading large SVGs fully into memory.
+    let mut file = fs::File::open(path).ok()?;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).ok()?;
+        if read == 0 {
+            break;
+        }
+        buf[..read].hash(&mut hasher);
+    }
 
     Some(format!("{:0x}", hasher.finish()))
 }
 
-fn main() -> anyhow::Result<()> {
-    eprintln!("{}", std::env::args().collect::<Vec<String>>().join(" "));
-    let opt = Args::parse();
+/// Formats that Inkscape can't produce directly, which we instead rasterize to PNG through
+/// Inkscape and then re-encode ourselves with the `image` crate.
+fn is_post_rasterized_format(format: &str) -> bool {
+    matches!(format, "webp" | "jpg" | "jpeg")
+}
 
-    let mut cache_dir = env::temp_dir();
-    cache_dir.push("rsvg-convert-cache");
+/// Decodes the PNG at `png_path` and re-encodes it as `format` (`webp`, `jpg`/`jpeg`) to
+/// `output_path`, applying `quality` and flattening transparency onto `background` for formats
+/// without an alpha channel.
+fn reencode_raster(png_path: &Path, output_path: &Path, format: &str, quality: u8, background: &str) -> anyhow::Result<()> {
+    let image = image::open(png_path)
+        .with_context(|| format!("Failed to decode rasterized output: {}", png_path.display()))?;
 
-    if !cache_dir.exists() {
-        fs::create_dir(&cache_dir).context("failed to create temporary directory")?;
+    match format {
+        "webp" => {
+            // The `image` crate only exposes lossless WebP encoding (lossy encoding relied on
+            // the C libwebp dependency, which was dropped); `--quality` has no effect here.
+            let encoder = image::codecs::webp::WebPEncoder::new_lossless(
+                fs::File::create(output_path).context("Failed to create output file")?,
+            );
+            image.write_with_encoder(encoder).context("Failed to encode WebP output")?;
+        }
+        "jpg" | "jpeg" => {
+            let background = parse_background(background)?;
+            let flattened = flatten_onto(&image.to_rgba8(), background);
+            let mut file = fs::File::create(output_path).context("Failed to create output file")?;
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, quality);
+            flattened
+                .write_with_encoder(encoder)
+                .context("Failed to encode JPEG output")?;
+        }
+        x => unreachable!("{x} is not a post-rasterized format"),
     }
 
-    let (output_path, exists) = match hash_input(&opt) {
-        Some(hash) => {
-            let path = cache_dir.join(hash).with_extension(&opt.format);
-            let exists = path.exists();
-            (path, exists)
+    Ok(())
+}
+
+/// Parses a `#rrggbb` hex string into an opaque RGB color.
+fn parse_background(value: &str) -> anyhow::Result<image::Rgb<u8>> {
+    let hex = value.trim_start_matches('#');
+    anyhow::ensure!(hex.len() == 6, "Invalid background color: {value} (expected #rrggbb)");
+    let r = u8::from_str_radix(&hex[0..2], 16).context("Invalid background color")?;
+    let g = u8::from_str_radix(&hex[2..4], 16).context("Invalid background color")?;
+    let b = u8::from_str_radix(&hex[4..6], 16).context("Invalid background color")?;
+    Ok(image::Rgb([r, g, b]))
+}
+
+/// Flattens an RGBA image onto an opaque background, alpha-blending each pixel.
+fn flatten_onto(image: &image::RgbaImage, background: image::Rgb<u8>) -> image::DynamicImage {
+    let mut out = image::RgbImage::new(image.width(), image.height());
+    for (dst, src) in out.pixels_mut().zip(image.pixels()) {
+        let alpha = src[3] as f64 / 255.0;
+        let blend = |fg: u8, bg: u8| ((fg as f64 * alpha) + (bg as f64 * (1.0 - alpha))).round() as u8;
+        *dst = image::Rgb([
+            blend(src[0], background[0]),
+            blend(src[1], background[1]),
+            blend(src[2], background[2]),
+        ]);
+    }
+    image::DynamicImage::ImageRgb8(out)
+}
+
+/// A raster format we can resample ourselves after export, as opposed to a vector format that
+/// Inkscape must produce at its final size directly.
+fn is_raster_format(format: &str) -> bool {
+    format == "png" || is_post_rasterized_format(format) || format == "term"
+}
+
+/// Formats that Inkscape doesn't export directly, so we always render to an intermediate PNG
+/// and build the final output ourselves.
+fn needs_intermediate_png(format: &str) -> bool {
+    is_post_rasterized_format(format) || format == "term"
+}
+
+/// Renders `png_path` as a truecolor (or 8-color) terminal preview and writes the resulting
+/// escape sequences to `output_path`.
+fn render_term_preview(png_path: &Path, output_path: &Path) -> anyhow::Result<()> {
+    let image = image::open(png_path)
+        .with_context(|| format!("Failed to decode rasterized output: {}", png_path.display()))?;
+    let (columns, rows) = term_preview::terminal_size_or_default();
+    let rendered = term_preview::render(&image, columns, rows, term_preview::supports_truecolor());
+    fs::write(output_path, rendered).context("Failed to write terminal preview")
+}
+
+fn parse_resize_filter(value: &str) -> anyhow::Result<image::imageops::FilterType> {
+    use image::imageops::FilterType;
+    Ok(match value {
+        "nearest" => FilterType::Nearest,
+        "triangle" => FilterType::Triangle,
+        "catmull-rom" => FilterType::CatmullRom,
+        "gaussian" => FilterType::Gaussian,
+        "lanczos3" => FilterType::Lanczos3,
+        x => anyhow::bail!("Unknown resize filter: {x}"),
+    })
+}
+
+/// Rewrites the raster image at `path` in place, scaling its width and height independently so
+/// that an image exported at the uniform `export_dpi` ends up at the anisotropic `dpi_x`/`dpi_y`
+/// resolution the user actually asked for.
+fn resample_for_dpi(path: &Path, dpi_x: f64, dpi_y: f64, export_dpi: f64, filter: image::imageops::FilterType) -> anyhow::Result<()> {
+    let image = image::open(path).with_context(|| format!("Failed to decode rasterized output: {}", path.display()))?;
+    let width = ((image.width() as f64) * (dpi_x / export_dpi)).round().max(1.0) as u32;
+    let height = ((image.height() as f64) * (dpi_y / export_dpi)).round().max(1.0) as u32;
+    image.resize_exact(width, height, filter).save(path).with_context(|| format!("Failed to write resampled output: {}", path.display()))
+}
+
+/// Works out the `--export-width`/`--export-height` pair to hand to Inkscape, taking
+/// `--keep-aspect-ratio` into account.
+///
+/// With `-a` set, the SVG's intrinsic size (read via [`svg_metadata`]) is used to fill in
+/// whichever of width/height was omitted, or to fit the image inside a fully-specified box
+/// without distorting it. Without `-a`, or if the intrinsic size can't be determined, the
+/// requested width/height are passed through unchanged.
+fn resolve_dimensions(opt: &Args, input: Option<&Path>) -> (Option<u64>, Option<u64>) {
+    if !opt.keep_aspect_ratio {
+        return (opt.width, opt.height);
+    }
+
+    let natural = input.and_then(|path| svg_metadata::read_svg_dimensions(path, opt.dpi_x));
+    let Some(natural) = natural else {
+        return (opt.width, opt.height);
+    };
+
+    match (opt.width, opt.height) {
+        (Some(width), None) => (Some(width), Some((width as f64 / natural.aspect_ratio()).round() as u64)),
+        (None, Some(height)) => (Some((height as f64 * natural.aspect_ratio()).round() as u64), Some(height)),
+        (Some(width), Some(height)) => {
+            // Fit inside the requested box without exceeding either dimension.
+            let scale = (width as f64 / natural.width).min(height as f64 / natural.height);
+            (Some((natural.width * scale).round() as u64), Some((natural.height * scale).round() as u64))
         }
-        None => (cache_dir.join("from_stdin").with_extension(&opt.format), false),
+        (None, None) => (None, None),
+    }
+}
+
+/// Returns the process-wide lock guarding conversions that target the given cache hash, creating
+/// one if this is the first time the hash has been seen.
+///
+/// Batch mode can run `convert_one` for several inputs concurrently; if two of them hash to the
+/// same cache entry (e.g. content-identical files under different stems), this serializes them
+/// onto the same lock so only one actually runs Inkscape/re-encodes, and the other waits and
+/// then reuses the now-cached result instead of racing it on the same files.
+fn cache_lock_for(hash: &str) -> Arc<Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = OnceLock::new();
+    let locks = LOCKS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut locks = locks.lock().unwrap_or_else(|e| e.into_inner());
+    locks.entry(hash.to_owned()).or_insert_with(|| Arc::new(Mutex::new(()))).clone()
+}
+
+/// Converts a single input (or stdin, if `input` is `None`) according to `opt`, writing the
+/// result to `output` (or stdout, if `output` is `None`).
+fn convert_one(opt: &Args, input: Option<PathBuf>, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let mut cache_dir = env::temp_dir();
+    cache_dir.push("rsvg-convert-cache");
+
+    // Use `create_dir_all` (idempotent) rather than a check-then-act `exists`/`create_dir`
+    // pair: with batch conversions running `convert_one` concurrently across threads, two
+    // workers can race to create the directory on a cold cache.
+    fs::create_dir_all(&cache_dir).context("failed to create temporary directory")?;
+
+    let hash = hash_input(opt, input.as_deref());
+    let output_path = match &hash {
+        Some(hash) => cache_dir.join(hash).with_extension(&opt.format),
+        None => cache_dir.join("from_stdin").with_extension(&opt.format),
     };
 
-    if !exists {
-        let mut cmd = Command::new("inkscape");
-        match opt.input {
-            Some(input) => {
-                eprintln!("Reading input from: {}", input.display());
-                cmd.arg(input);
+    // Hold the per-hash lock across the existence check and the conversion below, so that two
+    // threads converting content-identical inputs don't both see a cache miss and write the
+    // same intermediate/output files at once; the loser blocks here and then just reuses the
+    // winner's cached result.
+    let lock = hash.as_deref().map(cache_lock_for);
+    {
+        let _guard = lock.as_ref().map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+        let exists = hash.is_some() && output_path.exists();
+
+        if !exists {
+            let (width, height) = resolve_dimensions(opt, input.as_deref());
+            let post_rasterized = is_post_rasterized_format(&opt.format);
+            let is_term = opt.format == "term";
+            let needs_intermediate = needs_intermediate_png(&opt.format);
+            let resize_filter = parse_resize_filter(&opt.resize_filter)?;
+
+            anyhow::ensure!(
+                opt.dpi_x == opt.dpi_y || is_raster_format(&opt.format),
+                "Different DPI values for x and y are only supported for raster output formats (png, webp, jpg, term)"
+            );
+            let export_dpi = opt.dpi_x.max(opt.dpi_y);
+
+            // webp/jpg/term aren't export types Inkscape understands: export to an intermediate
+            // PNG alongside the final output, then build the final output ourselves below.
+            let inkscape_output_path = if needs_intermediate { output_path.with_extension("raster.png") } else { output_path.clone() };
+
+            let mut cmd = Command::new("inkscape");
+            match &input {
+                Some(input) => {
+                    eprintln!("Reading input from: {}", input.display());
+                    cmd.arg(input);
+                }
+                None => {
+                    eprintln!("Reading input from STDIN");
+                    cmd.arg("--pipe");
+                    cmd.stdin(std::process::Stdio::inherit());
+                }
             }
-            None => {
-                eprintln!("Reading input from STDIN");
-                cmd.arg("--pipe");
-                cmd.stdin(std::process::Stdio::inherit());
+            match if needs_intermediate { "png" } else { opt.format.as_str() } {
+                "png" => cmd.arg("--export-type=png"),
+                "pdf" => cmd.arg("--export-type=pdf"),
+                "ps" => cmd.arg("--export-type=ps"),
+                "eps" => cmd.arg("--export-type=eps"),
+                "wmf" => cmd.arg("--export-type=wmf"),
+                "emf" => cmd.arg("--export-type=emf"),
+                x => return Err(anyhow::anyhow!("Unsupported file format: {}", x)),
+            };
+            cmd.arg(&format!("--export-filename={}", inkscape_output_path.display()));
+            cmd.arg("--export-dpi").arg(export_dpi.to_string());
+
+            if let Some(width) = width {
+                cmd.arg("--export-width").arg(width.to_string());
+            }
+            if let Some(height) = height {
+                cmd.arg("--export-height").arg(height.to_string());
             }
-        }
-        match opt.format.as_str() {
-            "png" => cmd.arg("--export-type=png"),
-            "pdf" => cmd.arg("--export-type=pdf"),
-            "ps" => cmd.arg("--export-type=ps"),
-            "eps" => cmd.arg("--export-type=eps"),
-            "wmf" => cmd.arg("--export-type=wmf"),
-            "emf" => cmd.arg("--export-type=emf"),
-            x => return Err(anyhow::anyhow!("Unsupported file format: {}", x)),
-        };
-        cmd.arg(&format!("--export-filename={}", output_path.display()));
-
-        assert_eq!(
-            opt.dpi_x, opt.dpi_y,
-            "Different DPI values for x and y currently not supported"
-        );
-        cmd.arg("--export-dpi").arg(opt.dpi_x.to_string());
-
-        // TODO: check for `keep aspect ratio` and update width and height appropriately
-        if let Some(width) = opt.width {
-            cmd.arg("--export-width").arg(width.to_string());
-        }
-        if let Some(height) = opt.height {
-            cmd.arg("--export-height").arg(height.to_string());
-        }
 
-        eprintln!("Running: {:?}", cmd);
-        let output = cmd.output().context("Failed to execute inkscape")?;
+            eprintln!("Running: {:?}", cmd);
+            let output = cmd.output().context("Failed to execute inkscape")?;
 
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("Inkscape error:\n{}", error));
-        }
+            if !output.status.success() {
+                let error = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow::anyhow!("Inkscape error:\n{}", error));
+            }
 
-        let _ = std::io::stdout().write_all(&output.stdout);
-        let _ = std::io::stderr().write_all(&output.stderr);
-    }
-    else {
-        eprintln!("Loading from cache: {}", output_path.display());
+            let _ = std::io::stdout().write_all(&output.stdout);
+            let _ = std::io::stderr().write_all(&output.stderr);
+
+            // If width/height were resolved to explicit pixel dimensions (user-supplied, or
+            // computed from the SVG's intrinsic size under `-a`), Inkscape already exported at
+            // exactly that size regardless of DPI, so resampling again here would distort an
+            // already-correct image. Anisotropic DPI only needs a post-resample when DPI alone
+            // determined the size; if either `--width` or `--height` was given, both `--dpi-x`
+            // and `--dpi-y` are superseded and silently have no further effect on sizing.
+            if opt.dpi_x != opt.dpi_y && width.is_none() && height.is_none() {
+                resample_for_dpi(&inkscape_output_path, opt.dpi_x, opt.dpi_y, export_dpi, resize_filter)?;
+            }
+            else if opt.dpi_x != opt.dpi_y && (width.is_some() || height.is_some()) {
+                eprintln!(
+                    "Note: --dpi-x/--dpi-y are ignored because --width/--height was given; the image will not be resized anisotropically"
+                );
+            }
+
+            if post_rasterized {
+                reencode_raster(&inkscape_output_path, &output_path, &opt.format, opt.quality, &opt.background)?;
+                let _ = fs::remove_file(&inkscape_output_path);
+            }
+            else if is_term {
+                render_term_preview(&inkscape_output_path, &output_path)?;
+                let _ = fs::remove_file(&inkscape_output_path);
+            }
+        }
+        else {
+            eprintln!("Loading from cache: {}", output_path.display());
+        }
     }
 
-    if let Some(output) = opt.output {
+    if opt.format == "term" {
+        // The terminal preview is only meaningful written straight to the terminal, so it
+        // ignores `--output` rather than silently writing escape sequences to a file.
+        let mut data = std::fs::File::open(&output_path)
+            .with_context(|| format!("No output was generated: {}", output_path.display()))?;
+        std::io::copy(&mut data, &mut std::io::stdout().lock())
+            .context("Failed to write terminal preview to stdout")?;
+    }
+    else if let Some(output) = output {
         fs::copy(output_path, output).context("Failed to copy output to destination")?;
     }
     else {
@@ -162,3 +411,62 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Derives the output path for one input of a batch conversion: the input's file stem, placed
+/// in `output_dir` with the extension for `format`.
+fn batch_output_path(output_dir: &Path, input: &Path, format: &str) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default();
+    output_dir.join(stem).with_extension(format)
+}
+
+fn main() -> anyhow::Result<()> {
+    eprintln!("{}", std::env::args().collect::<Vec<String>>().join(" "));
+    let opt = Args::parse();
+
+    if opt.input.len() > 1 {
+        let output_dir = opt
+            .output_dir
+            .as_ref()
+            .context("--output-dir is required when converting multiple inputs")?;
+        anyhow::ensure!(opt.output.is_none(), "--output can't be combined with multiple inputs; use --output-dir instead");
+        // `term` writes its preview straight to stdout rather than to a file; with multiple
+        // inputs converting in parallel, their previews would interleave into a garbled mess.
+        anyhow::ensure!(opt.format != "term", "--format term doesn't support multiple inputs; convert them one at a time");
+        fs::create_dir_all(output_dir).context("Failed to create --output-dir")?;
+
+        // Two inputs with the same file stem (e.g. `a/icon.svg` and `b/icon.svg`) would map to
+        // the same output path and race each other, so reject that up front.
+        let mut seen_stems = std::collections::HashSet::new();
+        for input in &opt.input {
+            let stem = input.file_stem().unwrap_or_default().to_owned();
+            anyhow::ensure!(
+                seen_stems.insert(stem),
+                "Multiple inputs share the file stem of {}; each must produce a distinct output filename",
+                input.display()
+            );
+        }
+
+        let results: Vec<(PathBuf, anyhow::Result<()>)> = opt
+            .input
+            .par_iter()
+            .map(|input| {
+                let output = batch_output_path(output_dir, input, &opt.format);
+                (input.clone(), convert_one(&opt, Some(input.clone()), Some(output)))
+            })
+            .collect();
+
+        let failures = results.iter().filter(|(_, result)| result.is_err()).count();
+        for (input, result) in &results {
+            match result {
+                Ok(()) => eprintln!("OK:   {}", input.display()),
+                Err(err) => eprintln!("FAIL: {}: {err:#}", input.display()),
+            }
+        }
+        eprintln!("{} succeeded, {failures} failed", results.len() - failures);
+
+        anyhow::ensure!(failures == 0, "{failures} of {} conversions failed", results.len());
+        return Ok(());
+    }
+
+    convert_one(&opt, opt.input.first().cloned(), opt.output.clone())
+}
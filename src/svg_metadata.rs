@@ -0,0 +1,108 @@
+//! Minimal reader for the intrinsic size of an SVG document.
+//!
+//! Inkscape doesn't expose a way to query an SVG's natural dimensions before rendering it, so
+//! to support `--keep-aspect-ratio` we need to recover the `width`/`height`/`viewBox` of the
+//! root `<svg>` element ourselves.
+
+use std::{fs, path::Path};
+
+/// The natural size of an SVG document, in pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct SvgDimensions {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl SvgDimensions {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width / self.height
+    }
+}
+
+/// Reads the `width`, `height`, and `viewBox` attributes off the root `<svg>` element and
+/// resolves them to a pixel size, converting unit suffixes using `dpi_x`.
+///
+/// Returns `None` if the file can't be read or no usable size can be determined (e.g. the
+/// element is missing both `width`/`height` and a `viewBox`).
+pub fn read_svg_dimensions(path: &Path, dpi_x: f64) -> Option<SvgDimensions> {
+    let contents = fs::read_to_string(path).ok()?;
+    let svg_tag = extract_svg_tag(&contents)?;
+
+    let width = extract_attr(svg_tag, "width").and_then(|v| parse_length(&v, dpi_x));
+    let height = extract_attr(svg_tag, "height").and_then(|v| parse_length(&v, dpi_x));
+    let view_box = extract_attr(svg_tag, "viewBox").and_then(|v| parse_view_box(&v));
+
+    match (width, height) {
+        (Some(width), Some(height)) => Some(SvgDimensions { width, height }),
+        _ => {
+            // Fall back to the `viewBox` size (and combine it with whichever of width/height
+            // was given, if any) so a document that only specifies a viewBox still reports a
+            // usable aspect ratio.
+            let (vb_width, vb_height) = view_box?;
+            Some(SvgDimensions { width: width.unwrap_or(vb_width), height: height.unwrap_or(vb_height) })
+        }
+    }
+}
+
+/// Finds the opening `<svg ...>` tag and returns its attribute text (everything between `<svg`
+/// and the closing `>`).
+fn extract_svg_tag(contents: &str) -> Option<&str> {
+    let start = contents.find("<svg")?;
+    let rest = &contents[start + "<svg".len()..];
+    let end = rest.find('>')?;
+    Some(&rest[..end])
+}
+
+/// Extracts the raw string value of an XML attribute from a tag's attribute text.
+///
+/// Matches are required to start at the beginning of the tag text or be preceded by whitespace,
+/// so a query for `width` doesn't match the tail of `stroke-width`.
+fn extract_attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let needle = format!("{name}=\"");
+    let mut search_start = 0;
+    loop {
+        let found = tag[search_start..].find(&needle)? + search_start;
+        let boundary_ok = match tag[..found].chars().next_back() {
+            None => true,
+            Some(c) => c.is_whitespace(),
+        };
+        if boundary_ok {
+            let start = found + needle.len();
+            let end = tag[start..].find('"')? + start;
+            return Some(&tag[start..end]);
+        }
+        search_start = found + needle.len();
+    }
+}
+
+/// Parses a CSS-style length (e.g. `"210mm"`, `"100"`, `"12pt"`) into pixels.
+fn parse_length(value: &str, dpi_x: f64) -> Option<f64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit() && c != '.' && c != '-') {
+        Some(idx) => (&value[..idx], value[idx..].trim()),
+        None => (value, ""),
+    };
+    let number: f64 = number.parse().ok()?;
+
+    // CSS px is defined as 1/96th of an inch; other units convert through the requested DPI.
+    let px_per_unit = match unit {
+        "" | "px" => 1.0,
+        "in" => dpi_x,
+        "pt" => dpi_x / 72.0,
+        "pc" => dpi_x / 6.0,
+        "mm" => dpi_x / 25.4,
+        "cm" => dpi_x / 2.54,
+        _ => return None,
+    };
+    Some(number * px_per_unit)
+}
+
+/// Parses a `viewBox="min-x min-y width height"` attribute into a `(width, height)` pair.
+fn parse_view_box(value: &str) -> Option<(f64, f64)> {
+    let mut parts = value.split_whitespace();
+    let _min_x = parts.next()?;
+    let _min_y = parts.next()?;
+    let width: f64 = parts.next()?.parse().ok()?;
+    let height: f64 = parts.next()?.parse().ok()?;
+    Some((width, height))
+}
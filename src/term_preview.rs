@@ -0,0 +1,81 @@
+//! Renders a rasterized image directly to the terminal as colored text, for the `--format term`
+//! preview mode.
+//!
+//! Each character cell encodes two vertically adjacent pixels using the Unicode upper-half-block
+//! character `▀`: the top pixel becomes the 24-bit foreground color and the bottom pixel becomes
+//! the background color, the same trick tools like `viu` and `termimage` use.
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+
+/// Returns the terminal's column/row count, falling back to 80x24 if it can't be determined
+/// (e.g. output is piped to a file).
+pub fn terminal_size_or_default() -> (u32, u32) {
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(w), terminal_size::Height(h))) => (w as u32, h as u32),
+        None => (80, 24),
+    }
+}
+
+/// Whether the terminal advertises 24-bit color support via `$COLORTERM`.
+pub fn supports_truecolor() -> bool {
+    matches!(std::env::var("COLORTERM").as_deref(), Ok("truecolor") | Ok("24bit"))
+}
+
+/// Renders `image` as a string of ANSI escape sequences sized to fit within `columns` x `rows`
+/// character cells, downscaling first so each cell covers one source pixel pair.
+///
+/// The image is scaled to fit within the available cells without distorting its aspect ratio,
+/// then centered on a transparent canvas of the full cell grid (letterboxed/pillarboxed) so a
+/// terminal window whose aspect doesn't match the image doesn't squash or stretch it.
+pub fn render(image: &DynamicImage, columns: u32, rows: u32, truecolor: bool) -> String {
+    let target_width = columns.max(1);
+    let target_height = rows.max(1) * 2;
+
+    let scale = (target_width as f64 / image.width() as f64).min(target_height as f64 / image.height() as f64);
+    let fit_width = ((image.width() as f64 * scale).round() as u32).clamp(1, target_width);
+    let fit_height = ((image.height() as f64 * scale).round() as u32).clamp(1, target_height);
+    let resized = image.resize_exact(fit_width, fit_height, image::imageops::FilterType::Triangle).to_rgba8();
+
+    let mut canvas = image::RgbaImage::new(target_width, target_height);
+    let x_offset = ((target_width - fit_width) / 2) as i64;
+    let y_offset = ((target_height - fit_height) / 2) as i64;
+    image::imageops::overlay(&mut canvas, &resized, x_offset, y_offset);
+
+    let mut out = String::new();
+    for row in 0..rows {
+        for col in 0..columns {
+            let top = *canvas.get_pixel(col, row * 2);
+            let bottom = *canvas.get_pixel(col, row * 2 + 1);
+            push_cell(&mut out, top, bottom, truecolor);
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+fn push_cell(out: &mut String, fg: Rgba<u8>, bg: Rgba<u8>, truecolor: bool) {
+    // Fully transparent on both halves (letterbox padding, or a transparent area of the image):
+    // leave it blank instead of drawing a colored block.
+    if fg[3] == 0 && bg[3] == 0 {
+        out.push(' ');
+        return;
+    }
+
+    if truecolor {
+        out.push_str(&format!("\x1b[38;2;{};{};{}m", fg[0], fg[1], fg[2]));
+        out.push_str(&format!("\x1b[48;2;{};{};{}m", bg[0], bg[1], bg[2]));
+    } else {
+        out.push_str(&format!("\x1b[{}m", 30 + nearest_3bit_color(fg)));
+        out.push_str(&format!("\x1b[{}m", 40 + nearest_3bit_color(bg)));
+    }
+    out.push(UPPER_HALF_BLOCK);
+}
+
+/// Maps a color to the nearest of the 8 basic ANSI colors (0=black .. 7=white) by rounding each
+/// channel to on/off.
+fn nearest_3bit_color(color: Rgba<u8>) -> u8 {
+    let bit = |c: u8| (c > 127) as u8;
+    bit(color[0]) | (bit(color[1]) << 1) | (bit(color[2]) << 2)
+}